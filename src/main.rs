@@ -1,15 +1,17 @@
 use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::error::Error;
-use std::f32::consts::PI;
 use std::fs::File;
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use chrono::{NaiveDateTime};
 use ordered_float::OrderedFloat;
-use serde::{de, Deserialize, Deserializer, Serialize};
+use rstar::{RTree, RTreeObject, PointDistance, AABB};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)] // City/state fields mirror the full input schema; kept for completeness/debugging even though the search only reads the coordinates.
 struct Data {
     load_id: i32,
     origin_city: String,
@@ -56,11 +58,50 @@ fn naive_date_time_from_input<'de, D>(deserializer: D) -> Result<NaiveDateTime,
 struct Output {
     input_trip_id: i32,
     load_ids: Vec<i32>,
+    legs: Vec<Leg>,
+    // The whole trip's geometry as a single Google-style encoded polyline, ready to drop onto a
+    // web map.
+    polyline: String,
+}
+
+/// One picked-up-and-delivered load within a trip, with enough timing and geometry to render a
+/// schedule and a map for that segment.
+#[derive(Serialize)]
+struct Leg {
+    load_id: i32,
+    from: (f64, f64),
+    to: (f64, f64),
+    #[serde(serialize_with = "naive_date_time_as_epoch_millis")]
+    depart_time: NaiveDateTime,
+    #[serde(serialize_with = "naive_date_time_as_epoch_millis")]
+    arrive_time: NaiveDateTime,
+    miles: f64,
+    revenue: i32,
+    fuel_cost: f64,
+}
+
+fn naive_date_time_as_epoch_millis<S>(time: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+{
+    serializer.serialize_i64(time.and_utc().timestamp_millis())
 }
 
 const MILES_TRAVELLED_PER_HOUR: i32 = 55;
 const FUEL_COST_PER_MILE: f64 = 0.40;
 
+// How much later than a load's pickup_date_time a truck is still allowed to show up and take
+// it. Past this the load is considered gone and the edge is pruned.
+const MAX_PICKUP_LATENESS_SECONDS: i64 = 4 * 3600;
+
+// How far (in miles) a truck is willing to deadhead from its current location to pick up
+// another load. Keeps the R-tree query from handing back origins on the other side of the map.
+const DEADHEAD_RADIUS_MILES: f64 = 50.0;
+
+// Granularity (in seconds) at which we bucket a node's arrival time for the closed set. Two
+// visits to the same location within the same bucket are treated as the same search state.
+const TIME_QUANTUM_SECONDS: i64 = 3600;
+
 fn get_geodesic_distance(start: (f64, f64), end: (f64, f64)) -> f64 {
     const RADIUS_OF_EARTH: i32 = 6371000; // metres
     const DEGREES_TO_RADIANS: f64 = std::f64::consts::PI / 180.;
@@ -95,72 +136,398 @@ fn load_input<P: AsRef<Path>>(path: P) -> Result<Vec<Input>, Box<dyn Error>> {
     Ok(data)
 }
 
+/// A source of freight loads. Lets the search run against the proprietary 123Loadboard JSON
+/// export or any other feed that can be mapped onto `Data`, without touching the solver.
+trait LoadSource {
+    fn loads(&self) -> Result<Vec<Data>, Box<dyn Error>>;
+}
+
+struct JsonLoadSource {
+    path: PathBuf,
+}
+
+impl LoadSource for JsonLoadSource {
+    fn loads(&self) -> Result<Vec<Data>, Box<dyn Error>> {
+        load_data(&self.path)
+    }
+}
+
+/// A row from a CSV/GTFS-style export, with the same fields as `Data` but the date left as a
+/// plain string until it's parsed.
+#[derive(Deserialize)]
+struct CsvRow {
+    load_id: i32,
+    origin_city: String,
+    origin_state: String,
+    origin_latitude: f64,
+    origin_longitude: f64,
+    destination_city: String,
+    destination_state: String,
+    destination_latitude: f64,
+    destination_longitude: f64,
+    amount: i32,
+    pickup_date_time: String,
+}
+
+impl TryFrom<CsvRow> for Data {
+    type Error = chrono::ParseError;
+
+    fn try_from(row: CsvRow) -> Result<Data, Self::Error> {
+        Ok(Data {
+            load_id: row.load_id,
+            origin_city: row.origin_city,
+            origin_state: row.origin_state,
+            origin_latitude: row.origin_latitude,
+            origin_longitude: row.origin_longitude,
+            destination_city: row.destination_city,
+            destination_state: row.destination_state,
+            destination_latitude: row.destination_latitude,
+            destination_longitude: row.destination_longitude,
+            amount: row.amount,
+            pickup_date_time: NaiveDateTime::parse_from_str(&row.pickup_date_time, "%Y-%m-%d %H:%M:%S")?,
+        })
+    }
+}
+
+struct CsvLoadSource {
+    path: PathBuf,
+}
+
+impl LoadSource for CsvLoadSource {
+    fn loads(&self) -> Result<Vec<Data>, Box<dyn Error>> {
+        let mut reader = csv::Reader::from_path(&self.path)?;
+        let mut loads = Vec::new();
+        for record in reader.deserialize::<CsvRow>() {
+            loads.push(Data::try_from(record?)?);
+        }
+        Ok(loads)
+    }
+}
+
+/// An admissible upper bound on how much more profit could still be earned between `from` and
+/// `to`: the total `amount` of every load whose `pickup_date_time` falls in that window, with
+/// fuel cost ignored. Ignoring fuel only ever overstates profit, so the bound stays optimistic.
+/// The window is widened to start `MAX_PICKUP_LATENESS_SECONDS` before `from`, since a load that
+/// became available just before `from` can still be picked up late within that grace period.
+fn remaining_profit_bound(loads_by_pickup: &[(NaiveDateTime, i32)], prefix: &[i64], from: NaiveDateTime, to: NaiveDateTime) -> f64 {
+    if from > to {
+        return 0.;
+    }
+
+    let earliest_reachable = from - chrono::Duration::seconds(MAX_PICKUP_LATENESS_SECONDS);
+    let lo = loads_by_pickup.partition_point(|(pickup_time, _)| *pickup_time < earliest_reachable);
+    let hi = loads_by_pickup.partition_point(|(pickup_time, _)| *pickup_time <= to);
+
+    (prefix[hi] - prefix[lo]) as f64
+}
+
+// A (latitude, longitude) point in the search graph.
+type Location = (OrderedFloat<f64>, OrderedFloat<f64>);
+
+// A location paired with its visit's quantized timestamp, the key `closed`/`came_from` index
+// on so that revisiting the same coordinate at a different time bucket doesn't collide.
+type VisitKey = (Location, i64);
+
 #[derive(Debug)]
 struct Node {
-    location: (OrderedFloat<f64>, OrderedFloat<f64>),
+    location: Location,
 
-    parent: Option<(OrderedFloat<f64>, OrderedFloat<f64>)>,
+    // The (location, quantized_time) key of the visit that produced this node, matching
+    // whatever key `closed`/`came_from` index on, so the exact visit can be looked back up
+    // later instead of just its bare location.
+    parent: Option<VisitKey>,
+    load_id: Option<i32>, // The load_id of the edge that produced this node, if any.
     time: NaiveDateTime,
 
+    // The edge that produced this node, kept around so the winning path's itinerary can report
+    // a per-leg schedule instead of just the ordered load_ids.
+    depart_time: NaiveDateTime,
+    leg_distance: f64,
+    leg_amount: i32,
+
     money_earned: f64,
     distance_covered: f64,
     h: f64,
+
+    // The load_ids picked up so far on the path leading to this node, shared (and only cloned
+    // on write) with every other node along that path. A reposition edge can otherwise lead a
+    // truck straight back to an already-serviced load's origin and pick it up again for the
+    // same full amount every lap, which strictly increases profit and so is never pruned by
+    // the closed-set dominance check alone.
+    used_loads: Rc<HashSet<i32>>,
 }
 
 impl Node {
-    fn new(location: (OrderedFloat<f64>, OrderedFloat<f64>)) -> Node {
+    fn new(location: Location) -> Node {
         Node {
             location,
             parent: None,
-            time: chrono::naive::MAX_DATETIME,
+            load_id: None,
+            time: NaiveDateTime::MAX,
+
+            depart_time: NaiveDateTime::MAX,
+            leg_distance: 0.,
+            leg_amount: 0,
 
             money_earned: 0.,
             distance_covered: 0.,
             h: f64::MIN,
+            used_loads: Rc::new(HashSet::new()),
         }
     }
 
-    fn calculate_heuristic(&self) -> f64 {
-        self.h
+    // Net profit earned so far: revenue minus the fuel burned getting here.
+    fn net_profit(&self) -> f64 {
+        self.money_earned - self.distance_covered * FUEL_COST_PER_MILE
     }
 }
 
-impl Ord for Node {
+/// Which strategy the search uses to order the open set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Ignore profit entirely and expand in the order nodes were discovered.
+    Bfs,
+    /// Always expand the node with the highest heuristic upper bound, ignoring profit so far.
+    Greedy,
+    /// Classic `f = g + h`: profit so far plus an admissible bound on profit still reachable.
+    AStar,
+}
+
+impl Mode {
+    fn from_arg(arg: &str) -> Option<Mode> {
+        match arg.to_lowercase().as_str() {
+            "bfs" => Some(Mode::Bfs),
+            "greedy" => Some(Mode::Greedy),
+            "astar" | "a-star" | "a*" => Some(Mode::AStar),
+            _ => None,
+        }
+    }
+}
+
+// The open set orders on this key rather than on `Node` directly, since the right priority
+// depends on which `Mode` the search is running in.
+struct HeapEntry {
+    priority: OrderedFloat<f64>,
+    node: Node,
+}
+
+impl Ord for HeapEntry {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(&other).unwrap_or_else(|| self.time.cmp(&other.time))
+        self.priority.cmp(&other.priority)
     }
 }
 
-impl PartialOrd for Node {
+impl PartialOrd for HeapEntry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.money_earned.partial_cmp(&other.money_earned)
+        Some(self.cmp(other))
     }
 }
 
-impl Eq for Node {}
+impl Eq for HeapEntry {}
 
-impl PartialEq for Node {
+impl PartialEq for HeapEntry {
     fn eq(&self, other: &Self) -> bool {
-        self.time == other.time && self.money_earned == other.money_earned
+        self.priority == other.priority
     }
 }
 
+fn priority(mode: Mode, g: f64, h: f64, insertion_order: u64) -> OrderedFloat<f64> {
+    OrderedFloat(match mode {
+        Mode::Bfs => -(insertion_order as f64),
+        Mode::Greedy => h,
+        Mode::AStar => g + h,
+    })
+}
+
+/// Everything needed to turn one step of the winning path back into a `Leg`, recorded when a
+/// node is expanded since the `Node` itself doesn't survive being popped off the heap.
+struct Visit {
+    load_id: Option<i32>,
+    parent: Option<VisitKey>,
+    depart_time: NaiveDateTime,
+    arrive_time: NaiveDateTime,
+    distance: f64,
+    amount: i32,
+}
+
 #[derive(Debug)]
 struct Edge {
     distance: f64,
     amount: i32,
-    destination: (OrderedFloat<f64>, OrderedFloat<f64>), // This technically references a Node but I can't find a way to do it safely.
+    destination: Location, // This technically references a Node but I can't find a way to do it safely.
+    pickup_time: NaiveDateTime, // The load isn't available at its origin until this time.
+    load_id: Option<i32>, // None for a reposition edge, since it doesn't carry a load.
+}
+
+// Rough miles-per-degree-of-latitude used to flatten (lat, lon) into a locally-planar space.
+const MILES_PER_DEGREE_LATITUDE: f64 = 69.0;
+
+/// Projects (lat, lon) degrees into an approximate local equirectangular space, in miles, by
+/// scaling longitude by `cos(reference_latitude)`. rstar requires the envelope's distance metric
+/// to match `PointDistance::distance_2` (see its `Envelope` docs), which in turn requires every
+/// point compared against the same planar frame — projecting each point by its own latitude
+/// instead would place points at different latitudes in different frames, so a single shared
+/// `reference_latitude` (the same one used to build the whole `RTree`) is used for every point.
+fn project_to_miles(point: (f64, f64), reference_latitude: f64) -> [f64; 2] {
+    let (lat, lon) = point;
+    let miles_per_degree_longitude = MILES_PER_DEGREE_LATITUDE * reference_latitude.to_radians().cos();
+    [lat * MILES_PER_DEGREE_LATITUDE, lon * miles_per_degree_longitude]
+}
+
+/// A load's pickup origin, indexed in an `RTree` so a truck stranded at a drop-off with no
+/// outgoing load can still find nearby work instead of being stuck waiting for an exact
+/// coordinate match. `projected` is `location` flattened into the shared planar frame once, up
+/// front, at tree-construction time, so every subsequent envelope/distance comparison against it
+/// is guaranteed to use the same frame as every other origin in the tree.
+struct LoadOrigin {
+    location: (f64, f64),
+    projected: [f64; 2],
+}
+
+impl RTreeObject for LoadOrigin {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        AABB::from_point(self.projected)
+    }
+}
+
+impl PointDistance for LoadOrigin {
+    fn distance_2(&self, point: &[f64; 2]) -> f64 {
+        let dx = self.projected[0] - point[0];
+        let dy = self.projected[1] - point[1];
+        dx * dx + dy * dy
+    }
+}
+
+/// Builds the zero-revenue "reposition" edges available from `current_coords`: every load origin
+/// within `DEADHEAD_RADIUS_MILES`, other than the truck's exact current location, as a deadhead
+/// hop the truck could take to reach it.
+fn reposition_edges(origins: &RTree<LoadOrigin>, current_coords: (f64, f64), reference_latitude: f64, current_time: NaiveDateTime) -> Vec<Edge> {
+    origins
+        .locate_within_distance(project_to_miles(current_coords, reference_latitude), DEADHEAD_RADIUS_MILES * DEADHEAD_RADIUS_MILES)
+        .filter(|origin| origin.location != current_coords)
+        .map(|origin| Edge {
+            distance: get_geodesic_distance(current_coords, origin.location),
+            amount: 0,
+            destination: (OrderedFloat(origin.location.0), OrderedFloat(origin.location.1)),
+            pickup_time: current_time, // Repositioning isn't a pickup, so there's nothing to wait for.
+            load_id: None,
+        })
+        .collect()
+}
+
+/// Walks the parent chain back from `best_terminal` to the start, collecting the ordered
+/// sequence of load_ids and per-leg itinerary that were actually picked up along the way, plus
+/// every waypoint visited (including reposition hops, which have no load_id and so contribute
+/// travel time and geometry but no leg).
+///
+/// `came_from` only remembers the single best-profit visit per (location, quantized_time) key,
+/// and a later, better-profit visit to one key can be reached via a path that runs back through
+/// another key whose own best visit is, at that point, still parented on the first key's earlier
+/// (now-overwritten) visit. That can leave two keys pointing at each other, so the walk is
+/// guarded with a seen-set and stops rather than looping forever.
+fn reconstruct_path(came_from: &HashMap<VisitKey, Visit>, best_terminal: Option<VisitKey>) -> (Vec<i32>, Vec<Leg>, Vec<(f64, f64)>) {
+    let mut load_ids = Vec::new();
+    let mut legs = Vec::new();
+    let mut waypoints = Vec::new();
+    let mut current = best_terminal;
+    let mut seen = HashSet::new();
+    while let Some(key) = current {
+        if !seen.insert(key) {
+            break;
+        }
+        let (location, _) = key;
+        waypoints.push((location.0.into_inner(), location.1.into_inner()));
+        match came_from.get(&key) {
+            Some(visit) => {
+                if let (Some(load_id), Some(parent)) = (visit.load_id, visit.parent) {
+                    let (parent_location, _) = parent;
+                    load_ids.push(load_id);
+                    legs.push(Leg {
+                        load_id,
+                        from: (parent_location.0.into_inner(), parent_location.1.into_inner()),
+                        to: (location.0.into_inner(), location.1.into_inner()),
+                        depart_time: visit.depart_time,
+                        arrive_time: visit.arrive_time,
+                        miles: visit.distance,
+                        revenue: visit.amount,
+                        fuel_cost: visit.distance * FUEL_COST_PER_MILE,
+                    });
+                }
+                current = visit.parent;
+            }
+            None => break,
+        }
+    }
+    load_ids.reverse();
+    legs.reverse();
+    waypoints.reverse();
+    (load_ids, legs, waypoints)
+}
+
+/// Encodes a trip's full list of visited waypoints, in order, as a single Google-style encoded
+/// polyline, ready to drop onto a web map. Includes reposition hops between loads so a deadhead
+/// doesn't draw a straight line across the gap.
+fn encode_trip_polyline(waypoints: &[(f64, f64)]) -> String {
+    let polyline_coords: Vec<geo_types::Coord<f64>> = waypoints
+        .iter()
+        .map(|(lat, lon)| geo_types::Coord { x: *lon, y: *lat })
+        .collect();
+    polyline::encode_coordinates(polyline_coords, 5).unwrap_or_default()
 }
 
 fn main() {
+    // Usage: codejam_xi <mode: bfs|greedy|astar> <data-path> <input-path> [--csv]
+    // `--csv` reads the data path as a CSV/GTFS-style export instead of 123Loadboard JSON.
+    let mut args = std::env::args().skip(1);
+    let mode = args.next().as_deref().and_then(Mode::from_arg).unwrap_or(Mode::AStar);
+    let data_path = args.next().expect("usage: codejam_xi <mode> <data-path> <input-path> [--csv]");
+    let input_path = args.next().expect("usage: codejam_xi <mode> <data-path> <input-path> [--csv]");
+    let use_csv = args.next().as_deref() == Some("--csv");
+
     // Load the data we're given.
-    let data = load_data("/Users/josh/Documents/Programming/codejam_xi/src/data/123Loadboard_CodeJam_2022_dataset.json").unwrap();
-    let input = load_input("/Users/josh/Documents/Programming/codejam_xi/src/data/123Loadboard_CodeJam_2022_input_sample_s300.json").unwrap();
+    let source: Box<dyn LoadSource> = if use_csv {
+        Box::new(CsvLoadSource { path: data_path.into() })
+    } else {
+        Box::new(JsonLoadSource { path: data_path.into() })
+    };
+    let data = source.loads().unwrap();
+    let input = load_input(&input_path).unwrap();
+
+    // Index every load's origin so the search can find nearby pickups even when nothing
+    // leaves from the truck's exact current coordinate. Every origin (and every later query
+    // point) is projected relative to this single reference latitude, so they all land in the
+    // same planar frame instead of each skewing the longitude scale by its own latitude.
+    let reference_latitude = if data.is_empty() {
+        0.
+    } else {
+        data.iter().map(|datum| datum.origin_latitude).sum::<f64>() / data.len() as f64
+    };
+    let origins: RTree<LoadOrigin> = RTree::bulk_load(
+        data.iter()
+            .map(|datum| {
+                let location = (datum.origin_latitude, datum.origin_longitude);
+                LoadOrigin { location, projected: project_to_miles(location, reference_latitude) }
+            })
+            .collect(),
+    );
+
+    // Sorted by pickup time with a running prefix sum, so `remaining_profit_bound` can answer
+    // "how much could still be earned between these two times" with a couple of binary searches
+    // instead of rescanning every load for every node we expand.
+    let mut loads_by_pickup: Vec<(NaiveDateTime, i32)> = data.iter().map(|datum| (datum.pickup_date_time, datum.amount)).collect();
+    loads_by_pickup.sort_by_key(|(pickup_time, _)| *pickup_time);
+    let mut pickup_amount_prefix = Vec::with_capacity(loads_by_pickup.len() + 1);
+    pickup_amount_prefix.push(0i64);
+    for (_, amount) in &loads_by_pickup {
+        pickup_amount_prefix.push(pickup_amount_prefix.last().unwrap() + *amount as i64);
+    }
 
     // Create the semi-graph.
     let mut nodes = HashMap::with_capacity(data.len());
     let mut neighbors = HashMap::with_capacity(data.len());
-    for datum in data {
+    for datum in &data {
         // Read the origin and destination from the line.
         let origin = (OrderedFloat(datum.origin_latitude), OrderedFloat(datum.origin_longitude));
         let destination = (OrderedFloat(datum.destination_latitude), OrderedFloat(datum.destination_longitude));
@@ -172,41 +539,114 @@ fn main() {
         nodes.entry(destination).or_insert(Node::new(origin));
 
         // Add an edge connecting the origin and destination nodes.
-        neighbors.entry(origin).or_insert(Vec::new()).push(Edge { distance: get_geodesic_distance((datum.origin_latitude, datum.origin_longitude), (datum.destination_latitude, datum.destination_longitude)), amount: datum.amount, destination });
+        neighbors.entry(origin).or_insert(Vec::new()).push(Edge { distance: get_geodesic_distance((datum.origin_latitude, datum.origin_longitude), (datum.destination_latitude, datum.destination_longitude)), amount: datum.amount, destination, pickup_time: datum.pickup_date_time, load_id: Some(datum.load_id) });
     }
 
     // Loop through all the inputs.
+    let mut outputs = Vec::with_capacity(input.len());
     for request in input {
         let mut start_node = Node::new((OrderedFloat(request.start_latitude), OrderedFloat(request.start_longitude)));
         start_node.time = request.start_time;
+        start_node.h = remaining_profit_bound(&loads_by_pickup, &pickup_amount_prefix, start_node.time, request.max_destination_time);
 
 
 
         let mut open = BinaryHeap::new();
-        let mut closed = Vec::new();
+        // Best net profit seen so far for a given (location, quantized_time) pair. A later pop
+        // of the same key with a worse profit is stale and gets skipped instead of re-expanded.
+        let mut closed: HashMap<VisitKey, f64> = HashMap::new();
+
+        // Remembers, for every (location, quantized_time) visit we've expanded, the leg that
+        // reached it (if any) and its parent visit, so the winning path's itinerary can be
+        // walked back out once the search ends. Keyed the same way as `closed`: a location
+        // alone isn't enough, since the same coordinate is routinely reached at many different
+        // times by unrelated branches of the search, and keying on location alone would let a
+        // later, unrelated visit clobber the one that actually produced the winning profit.
+        let mut came_from: HashMap<VisitKey, Visit> = HashMap::new();
+        let mut best_terminal: Option<VisitKey> = None;
+        let mut best_profit = f64::MIN;
+        let mut insertion_order = 0u64;
+
+        let start_priority = priority(mode, start_node.net_profit(), start_node.h, insertion_order);
+        open.push(HeapEntry { priority: start_priority, node: start_node });
+
+        while let Some(HeapEntry { node: current_node, .. }) = open.pop() {
+            let closed_key = (current_node.location, current_node.time.and_utc().timestamp() / TIME_QUANTUM_SECONDS);
+            let current_profit = current_node.net_profit();
+            if closed.get(&closed_key).is_some_and(|&best| best >= current_profit) {
+                continue;
+            }
+            closed.insert(closed_key, current_profit);
+
+            came_from.insert(closed_key, Visit {
+                load_id: current_node.load_id,
+                parent: current_node.parent,
+                depart_time: current_node.depart_time,
+                arrive_time: current_node.time,
+                distance: current_node.leg_distance,
+                amount: current_node.leg_amount,
+            });
+            if current_profit > best_profit {
+                best_profit = current_profit;
+                best_terminal = Some(closed_key);
+            }
 
-        open.push(start_node);
+            // Find nearby loads the truck could deadhead to even if none leave from here
+            // exactly, and offer them up as zero-revenue "reposition" edges.
+            let current_coords = (current_node.location.0.into_inner(), current_node.location.1.into_inner());
+            let repositions = reposition_edges(&origins, current_coords, reference_latitude, current_node.time);
 
-        while let Some(current_node) = open.pop() {
             // Check all neighbours
-            for edge in neighbors.get(&current_node.location).unwrap_or(&vec![]) {
-                let edge_cost = (current_node.money_earned + edge.amount as f64) - ((current_node.distance_covered + edge.distance) * FUEL_COST_PER_MILE);
+            for edge in neighbors.get(&current_node.location).unwrap_or(&vec![]).iter().chain(repositions.iter()) {
+                // A load already picked up earlier on this path can't be picked up again: its
+                // origin may still be reachable (directly or via reposition), but the freight
+                // is gone. Without this, a reposition edge back to a serviced origin lets the
+                // same load be "picked up" every lap for free profit, forever.
+                if edge.load_id.is_some_and(|load_id| current_node.used_loads.contains(&load_id)) {
+                    continue;
+                }
+
+                // The truck is already at the load's origin (current_node.location), so
+                // current_node.time is the arrival time there. If we're early, wait for the
+                // load to actually become available; if we're too late, it's gone.
+                let arrival_at_origin = current_node.time;
+                let lateness = arrival_at_origin.signed_duration_since(edge.pickup_time);
+                if lateness.num_seconds() > MAX_PICKUP_LATENESS_SECONDS {
+                    continue;
+                }
+                let departure_time = arrival_at_origin.max(edge.pickup_time);
+                let arrival_at_destination = departure_time + chrono::Duration::seconds((edge.distance / MILES_TRAVELLED_PER_HOUR as f64 * 3600.) as i64);
+
+                if arrival_at_destination > request.max_destination_time {
+                    continue;
+                }
+
+                let g = (current_node.money_earned + edge.amount as f64) - ((current_node.distance_covered + edge.distance) * FUEL_COST_PER_MILE);
+                let h = remaining_profit_bound(&loads_by_pickup, &pickup_amount_prefix, arrival_at_destination, request.max_destination_time);
+                let used_loads = match edge.load_id {
+                    Some(load_id) => {
+                        let mut used = (*current_node.used_loads).clone();
+                        used.insert(load_id);
+                        Rc::new(used)
+                    }
+                    None => Rc::clone(&current_node.used_loads),
+                };
                 let neighbor_node = Node {
-                    parent: Some(current_node.location),
-                    time: current_node.time + chrono::Duration::seconds((edge.distance / MILES_TRAVELLED_PER_HOUR as f64 * 3600.) as i64),
+                    location: edge.destination,
+                    parent: Some(closed_key),
+                    load_id: edge.load_id,
+                    time: arrival_at_destination,
+                    depart_time: departure_time,
+                    leg_distance: edge.distance,
+                    leg_amount: edge.amount,
                     money_earned: current_node.money_earned + edge.amount as f64,
                     distance_covered: current_node.distance_covered + edge.distance,
-                    h: edge_cost,
-                    ..current_node
+                    h,
+                    used_loads,
                 };
 
-                if (edge_cost)
-
-                if !closed.contains(&edge.destination) && edge_cost > neighbor_node.h {
-                    if neighbor_node.time < request.max_destination_time {
-                        open.push(neighbor_node);
-                    }
-                }
+                insertion_order += 1;
+                open.push(HeapEntry { priority: priority(mode, g, h, insertion_order), node: neighbor_node });
             }
 
             // // Check all other nodes.
@@ -228,5 +668,220 @@ fn main() {
             //     }
             // }
         }
+
+        let (load_ids, legs, waypoints) = reconstruct_path(&came_from, best_terminal);
+        let polyline = encode_trip_polyline(&waypoints);
+
+        outputs.push(Output { input_trip_id: request.input_trip_id, load_ids, legs, polyline });
     }
-}
\ No newline at end of file
+
+    let output_file = File::create("output.json").unwrap();
+    serde_json::to_writer(output_file, &outputs).unwrap();
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn mode_from_arg_accepts_known_aliases() {
+        assert_eq!(Mode::from_arg("bfs"), Some(Mode::Bfs));
+        assert_eq!(Mode::from_arg("Greedy"), Some(Mode::Greedy));
+        assert_eq!(Mode::from_arg("astar"), Some(Mode::AStar));
+        assert_eq!(Mode::from_arg("a-star"), Some(Mode::AStar));
+        assert_eq!(Mode::from_arg("a*"), Some(Mode::AStar));
+        assert_eq!(Mode::from_arg("dijkstra"), None);
+    }
+
+    #[test]
+    fn priority_bfs_favors_earlier_insertions() {
+        // Bfs ignores profit entirely and orders purely on insertion order, and `open` is a
+        // max-heap, so an earlier insertion must sort above a later one.
+        let earlier = priority(Mode::Bfs, 100., 100., 1);
+        let later = priority(Mode::Bfs, 0., 0., 2);
+        assert!(earlier > later);
+    }
+
+    #[test]
+    fn priority_greedy_ignores_profit_so_far() {
+        let higher_h = priority(Mode::Greedy, 0., 10., 1);
+        let lower_h = priority(Mode::Greedy, 1000., 5., 1);
+        assert!(higher_h > lower_h);
+    }
+
+    #[test]
+    fn priority_a_star_sums_profit_and_heuristic() {
+        assert_eq!(priority(Mode::AStar, 3., 4., 1), OrderedFloat(7.));
+    }
+
+    #[test]
+    fn remaining_profit_bound_sums_loads_in_window() {
+        let loads_by_pickup = vec![
+            (datetime("2022-01-01 00:00:00"), 100),
+            (datetime("2022-01-01 06:00:00"), 200),
+            (datetime("2022-01-01 12:00:00"), 300),
+        ];
+        let mut prefix = Vec::with_capacity(loads_by_pickup.len() + 1);
+        prefix.push(0i64);
+        for (_, amount) in &loads_by_pickup {
+            prefix.push(prefix.last().unwrap() + *amount as i64);
+        }
+
+        let bound = remaining_profit_bound(
+            &loads_by_pickup,
+            &prefix,
+            datetime("2022-01-01 06:00:00"),
+            datetime("2022-01-01 12:00:00"),
+        );
+        assert_eq!(bound, 500.);
+    }
+
+    #[test]
+    fn remaining_profit_bound_is_empty_when_from_is_after_to() {
+        let loads_by_pickup = vec![(datetime("2022-01-01 00:00:00"), 100)];
+        let prefix = vec![0, 100];
+
+        let bound = remaining_profit_bound(
+            &loads_by_pickup,
+            &prefix,
+            datetime("2022-01-02 00:00:00"),
+            datetime("2022-01-01 00:00:00"),
+        );
+        assert_eq!(bound, 0.);
+    }
+
+    #[test]
+    fn remaining_profit_bound_still_counts_loads_within_the_lateness_grace_period() {
+        // A load that became available 3 hours before `from`, less than
+        // MAX_PICKUP_LATENESS_SECONDS, is still reachable (the truck can show up late), so it
+        // must stay inside the bound.
+        let loads_by_pickup = vec![(datetime("2022-01-01 08:00:00"), 100)];
+        let mut prefix = Vec::with_capacity(loads_by_pickup.len() + 1);
+        prefix.push(0i64);
+        for (_, amount) in &loads_by_pickup {
+            prefix.push(prefix.last().unwrap() + *amount as i64);
+        }
+
+        let bound = remaining_profit_bound(
+            &loads_by_pickup,
+            &prefix,
+            datetime("2022-01-01 11:00:00"),
+            datetime("2022-01-01 12:00:00"),
+        );
+        assert_eq!(bound, 100.);
+    }
+
+    #[test]
+    fn reposition_edges_includes_only_origins_within_radius_and_excludes_current_location() {
+        let reference_latitude = 40.0;
+        let current = (40.0, -90.0);
+        let nearby = (40.0, -90.2); // a few miles away, well inside the deadhead radius.
+        let far = (41.0, -90.0); // ~69 miles north, outside the deadhead radius.
+        let origins: RTree<LoadOrigin> = RTree::bulk_load(
+            [current, nearby, far]
+                .into_iter()
+                .map(|location| LoadOrigin { location, projected: project_to_miles(location, reference_latitude) })
+                .collect(),
+        );
+
+        let edges = reposition_edges(&origins, current, reference_latitude, datetime("2022-01-01 00:00:00"));
+
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].destination, (OrderedFloat(nearby.0), OrderedFloat(nearby.1)));
+        assert_eq!(edges[0].load_id, None);
+        assert_eq!(edges[0].amount, 0);
+    }
+
+    #[test]
+    fn csv_load_source_round_trips_a_row_into_data() {
+        let mut path = std::env::temp_dir();
+        path.push("codejam_xi_test_csv_load_source_round_trips_a_row_into_data.csv");
+        std::fs::write(
+            &path,
+            "load_id,origin_city,origin_state,origin_latitude,origin_longitude,destination_city,destination_state,destination_latitude,destination_longitude,amount,pickup_date_time\n\
+             1,Springfield,IL,39.78,-89.65,Chicago,IL,41.88,-87.63,500,2022-01-01 08:00:00\n",
+        ).unwrap();
+
+        let loads = CsvLoadSource { path: path.clone() }.loads().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loads.len(), 1);
+        assert_eq!(loads[0].load_id, 1);
+        assert_eq!(loads[0].origin_city, "Springfield");
+        assert_eq!(loads[0].destination_latitude, 41.88);
+        assert_eq!(loads[0].amount, 500);
+        assert_eq!(loads[0].pickup_date_time, datetime("2022-01-01 08:00:00"));
+    }
+
+    #[test]
+    fn reconstruct_path_walks_the_parent_chain_into_order() {
+        let start = ((OrderedFloat(0.0), OrderedFloat(0.0)), 0i64);
+        let middle = ((OrderedFloat(1.0), OrderedFloat(0.0)), 0i64);
+        let end = ((OrderedFloat(1.0), OrderedFloat(1.0)), 1i64);
+
+        let mut came_from = HashMap::new();
+        came_from.insert(middle, Visit {
+            load_id: Some(1),
+            parent: Some(start),
+            depart_time: datetime("2022-01-01 00:00:00"),
+            arrive_time: datetime("2022-01-01 01:00:00"),
+            distance: 10.,
+            amount: 100,
+        });
+        came_from.insert(end, Visit {
+            load_id: None, // a reposition hop: travel and a new waypoint, but no leg.
+            parent: Some(middle),
+            depart_time: datetime("2022-01-01 01:00:00"),
+            arrive_time: datetime("2022-01-01 01:30:00"),
+            distance: 5.,
+            amount: 0,
+        });
+
+        let (load_ids, legs, waypoints) = reconstruct_path(&came_from, Some(end));
+
+        assert_eq!(load_ids, vec![1]);
+        assert_eq!(legs.len(), 1);
+        assert_eq!(legs[0].from, (0.0, 0.0));
+        assert_eq!(legs[0].to, (1.0, 0.0));
+        assert_eq!(waypoints, vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]);
+    }
+
+    #[test]
+    fn reconstruct_path_terminates_on_a_cyclic_parent_chain() {
+        // A reposition hop back to an already-serviced origin can let a later, higher-profit
+        // visit to one (location, quantized_time) key overwrite its `came_from` entry with a
+        // parent that is itself still parented on this key's own earlier visit, leaving two
+        // keys pointing at each other. Without the seen-set guard this would loop forever.
+        let a = ((OrderedFloat(0.0), OrderedFloat(0.0)), 0i64);
+        let b = ((OrderedFloat(1.0), OrderedFloat(0.0)), 0i64);
+
+        let mut came_from = HashMap::new();
+        came_from.insert(a, Visit {
+            load_id: Some(2),
+            parent: Some(b),
+            depart_time: datetime("2022-01-01 00:00:00"),
+            arrive_time: datetime("2022-01-01 01:00:00"),
+            distance: 10.,
+            amount: 500,
+        });
+        came_from.insert(b, Visit {
+            load_id: None,
+            parent: Some(a),
+            depart_time: datetime("2022-01-01 01:00:00"),
+            arrive_time: datetime("2022-01-01 02:00:00"),
+            distance: 10.,
+            amount: 0,
+        });
+
+        let (load_ids, legs, waypoints) = reconstruct_path(&came_from, Some(a));
+
+        // The walk must stop instead of looping forever; exactly what it recovers before
+        // detecting the cycle is secondary to simply terminating.
+        assert!(load_ids.len() <= 2);
+        assert!(legs.len() <= 2);
+        assert!(waypoints.len() <= 2);
+    }
+}